@@ -0,0 +1,143 @@
+use error::{Result, ResultExt};
+use lvm::{self, VolumeGroup};
+use serde_json::{self, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct Request {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn dispatch(vg: &mut VolumeGroup, req: &Request) -> Result<Value> {
+    match req.method.as_str() {
+        "lvs" => serde_json::to_value(lvm::lvs()?).chain_err(|| "failed to serialize lvs report"),
+        "vgs" => serde_json::to_value(lvm::vgs()?).chain_err(|| "failed to serialize vgs report"),
+        "create_volume" => {
+            let name = req.params["name"]
+                .as_str()
+                .ok_or("create_volume requires a string 'name'")?;
+            let size = req.params["size"]
+                .as_u64()
+                .ok_or("create_volume requires an integer 'size'")?;
+            let report = vg.create_volume(name, size)?.report()?;
+            serde_json::to_value(report).chain_err(|| "failed to serialize volume report")
+        }
+        "remove_volume" => {
+            let name = req.params["name"]
+                .as_str()
+                .ok_or("remove_volume requires a string 'name'")?;
+            let volume = vg.volumes()?
+                .into_iter()
+                .filter(|lv| lv.name == name)
+                .next()
+                .ok_or("Unable to find volume to remove")?;
+            volume.remove()?;
+            Ok(Value::Null)
+        }
+        "snapshot" => {
+            let origin = req.params["name"]
+                .as_str()
+                .ok_or("snapshot requires a string 'name'")?;
+            let snapshot = req.params["snapshot"]
+                .as_str()
+                .ok_or("snapshot requires a string 'snapshot'")?;
+            let volume = vg.volumes()?
+                .into_iter()
+                .filter(|lv| lv.name == origin)
+                .next()
+                .ok_or("Unable to find origin volume")?;
+            let report = volume.snapshot(snapshot)?.report()?;
+            serde_json::to_value(report).chain_err(|| "failed to serialize snapshot report")
+        }
+        other => Err(format!("unknown method: {}", other).into()),
+    }
+}
+
+fn handle_connection(vg: &mut VolumeGroup, stream: UnixStream) -> Result<()> {
+    let mut writer = stream
+        .try_clone()
+        .chain_err(|| "failed to clone control stream")?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .chain_err(|| "failed to read request")?;
+        if read == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(req) => {
+                let id = req.id.clone();
+                match dispatch(vg, &req) {
+                    Ok(result) => Response {
+                        id,
+                        result: Some(result),
+                        error: None,
+                    },
+                    Err(e) => Response {
+                        id,
+                        result: None,
+                        error: Some(e.to_string()),
+                    },
+                }
+            }
+            Err(e) => Response {
+                id: Value::Null,
+                result: None,
+                error: Some(e.to_string()),
+            },
+        };
+        let mut encoded =
+            serde_json::to_string(&response).chain_err(|| "failed to serialize response")?;
+        encoded.push('\n');
+        writer
+            .write_all(encoded.as_bytes())
+            .chain_err(|| "failed to write response")?;
+    }
+    Ok(())
+}
+
+/// Listen on a Unix socket and serve newline-delimited JSON requests,
+/// dispatching each into `vg`. Connections are served one at a time, each
+/// until its client disconnects; the listener then accepts the next one.
+pub fn serve<P>(mut vg: VolumeGroup, socket: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let listener =
+        UnixListener::bind(socket.as_ref()).chain_err(|| "failed to bind control socket")?;
+    for stream in listener.incoming() {
+        // A single misbehaving or disconnecting client must not bring down the
+        // listener; log the error and keep serving the next connection.
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("failed to accept control connection: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = handle_connection(&mut vg, stream) {
+            eprintln!("control connection error: {}", e);
+        }
+    }
+    Ok(())
+}
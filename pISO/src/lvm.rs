@@ -2,10 +2,143 @@ use error::{ErrorKind, Result, ResultExt};
 use std::fmt::Display;
 use std::str::FromStr;
 use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
 use serde_json;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// A byte count that carries its own unit awareness.
+///
+/// LVM reports sizes as strings with an optional suffix (`1.50g`, `512m`,
+/// `4096B`); lowercase suffixes are powers of 1024 and uppercase powers of
+/// 1000. `ByteSize` parses any of those into a plain byte count and renders
+/// back to a compact human string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+fn parse_byte_size(s: &str) -> ::std::result::Result<u64, String> {
+    // LVM prefixes approximate sizes with `<`/`>` (e.g. `<1.00g`) when the
+    // value is not forced to an exact unit; drop it before parsing.
+    let s = s.trim().trim_start_matches(|c| c == '<' || c == '>').trim();
+    if s.is_empty() {
+        return Err("empty byte size".into());
+    }
+    let last = s.chars().last().unwrap();
+    let (digits, multiplier) = if last.is_digit(10) || last == '.' {
+        (s, 1f64)
+    } else {
+        let multiplier = match last {
+            'B' => 1f64,
+            'k' => 1024f64,
+            'K' => 1000f64,
+            'm' => 1024f64 * 1024f64,
+            'M' => 1000f64 * 1000f64,
+            'g' => 1024f64 * 1024f64 * 1024f64,
+            'G' => 1000f64 * 1000f64 * 1000f64,
+            't' => 1024f64 * 1024f64 * 1024f64 * 1024f64,
+            'T' => 1000f64 * 1000f64 * 1000f64 * 1000f64,
+            other => return Err(format!("unknown size suffix '{}'", other)),
+        };
+        (&s[..s.len() - last.len_utf8()], multiplier)
+    };
+    let value: f64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid byte size '{}'", s))?;
+    Ok((value * multiplier).round() as u64)
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<ByteSize, String> {
+        parse_byte_size(s).map(ByteSize)
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        const UNITS: [(&str, u64); 4] = [
+            ("t", 1024 * 1024 * 1024 * 1024),
+            ("g", 1024 * 1024 * 1024),
+            ("m", 1024 * 1024),
+            ("k", 1024),
+        ];
+        for &(suffix, factor) in UNITS.iter() {
+            if self.0 >= factor {
+                return write!(f, "{:.2}{}", self.0 as f64 / factor as f64, suffix);
+            }
+        }
+        write!(f, "{}B", self.0)
+    }
+}
+
+impl ::std::ops::Add for ByteSize {
+    type Output = ByteSize;
+    fn add(self, other: ByteSize) -> ByteSize {
+        ByteSize(self.0 + other.0)
+    }
+}
+
+impl ::std::ops::Sub for ByteSize {
+    type Output = ByteSize;
+    /// Subtracts byte counts, saturating at zero rather than panicking on
+    /// underflow (e.g. `vg_free - vg_size`).
+    fn sub(self, other: ByteSize) -> ByteSize {
+        ByteSize(self.0.saturating_sub(other.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<ByteSize, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ByteSizeVisitor;
+
+        impl<'de> de::Visitor<'de> for ByteSizeVisitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str("a byte count as an integer or a unit-suffixed string")
+            }
+
+            fn visit_u64<E>(self, v: u64) -> ::std::result::Result<ByteSize, E>
+            where
+                E: de::Error,
+            {
+                Ok(ByteSize(v))
+            }
+
+            fn visit_str<E>(self, v: &str) -> ::std::result::Result<ByteSize, E>
+            where
+                E: de::Error,
+            {
+                parse_byte_size(v).map(ByteSize).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ByteSizeVisitor)
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Serialize the exact byte count, not the lossy `Display` string, so a
+        // value round-trips through the daemon wire unchanged. `Display` is for
+        // UI only.
+        serializer.serialize_u64(self.0)
+    }
+}
+
 fn from_str<'de, T, D>(deserializer: D) -> ::std::result::Result<T, D::Error>
 where
     T: FromStr,
@@ -16,17 +149,19 @@ where
     T::from_str(&s).map_err(de::Error::custom)
 }
 
-fn from_str_strip_unit<'de, T, D>(deserializer: D) -> ::std::result::Result<T, D::Error>
+fn from_str_strip_percent<'de, D>(deserializer: D) -> ::std::result::Result<f64, D::Error>
 where
-    T: FromStr,
-    T::Err: Display,
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
-    T::from_str(&s.trim_matches('B')).map_err(de::Error::custom)
+    let trimmed = s.trim_matches('%');
+    if trimmed.is_empty() {
+        return Ok(0.0);
+    }
+    f64::from_str(trimmed).map_err(de::Error::custom)
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct LogicalVolumeReport {
     pub lv_name: String,
     pub vg_name: String,
@@ -36,8 +171,7 @@ pub struct LogicalVolumeReport {
 
     pub lv_attr: String,
 
-    #[serde(deserialize_with = "from_str_strip_unit")]
-    pub lv_size: u64,
+    pub lv_size: ByteSize,
 
     #[serde(deserialize_with = "from_str")]
     pub lv_major: i32,
@@ -53,8 +187,13 @@ pub struct LogicalVolumeReport {
 
     pub pool_lv: String,
     pub origin: String,
-    pub data_percent: String,
-    pub metadata_percent: String,
+
+    #[serde(deserialize_with = "from_str_strip_percent")]
+    pub data_percent: f64,
+
+    #[serde(deserialize_with = "from_str_strip_percent")]
+    pub metadata_percent: f64,
+
     pub move_pv: String,
     pub copy_percent: String,
     pub mirror_log: String,
@@ -63,13 +202,120 @@ pub struct LogicalVolumeReport {
     pub lv_profile: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeType {
+    ThinPool,
+    ThinVolume,
+    Snapshot,
+    Linear,
+    Other(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeState {
+    Active,
+    Suspended,
+    InvalidSnapshot,
+    Other(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeHealth {
+    Ok,
+    Partial,
+    Unknown,
+    MetadataCorrupt,
+    Other(char),
+}
+
+/// Decoded view of the 10-character `lv_attr` field reported by `lvs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LvAttr {
+    pub volume_type: VolumeType,
+    pub state: VolumeState,
+    pub open: bool,
+    pub health: VolumeHealth,
+}
+
+impl LvAttr {
+    fn at(attr: &str, pos: usize) -> char {
+        attr.chars().nth(pos - 1).unwrap_or('-')
+    }
+
+    pub fn parse(attr: &str) -> LvAttr {
+        let volume_type = match LvAttr::at(attr, 1) {
+            't' => VolumeType::ThinPool,
+            'V' => VolumeType::ThinVolume,
+            's' => VolumeType::Snapshot,
+            '-' => VolumeType::Linear,
+            c => VolumeType::Other(c),
+        };
+        let state = match LvAttr::at(attr, 5) {
+            'a' => VolumeState::Active,
+            's' => VolumeState::Suspended,
+            'I' | 'i' => VolumeState::InvalidSnapshot,
+            c => VolumeState::Other(c),
+        };
+        let open = LvAttr::at(attr, 6) == 'o';
+        let health = match LvAttr::at(attr, 9) {
+            '-' => VolumeHealth::Ok,
+            'p' => VolumeHealth::Partial,
+            'X' => VolumeHealth::Unknown,
+            'm' => VolumeHealth::MetadataCorrupt,
+            c => VolumeHealth::Other(c),
+        };
+        LvAttr {
+            volume_type,
+            state,
+            open,
+            health,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state == VolumeState::Active
+    }
+
+    pub fn needs_repair(&self) -> bool {
+        self.state == VolumeState::InvalidSnapshot || self.health != VolumeHealth::Ok
+    }
+}
+
+impl LogicalVolumeReport {
+    /// The decoded `lv_attr` flag string.
+    pub fn attr(&self) -> LvAttr {
+        LvAttr::parse(&self.lv_attr)
+    }
+
+    /// The origin volume this one is a snapshot of, if any.
+    pub fn origin(&self) -> Option<&str> {
+        if self.origin.is_empty() {
+            None
+        } else {
+            Some(&self.origin)
+        }
+    }
+
+    /// The thin pool backing this volume, if it lives in one.
+    pub fn pool_lv(&self) -> Option<&str> {
+        if self.pool_lv.is_empty() {
+            None
+        } else {
+            Some(&self.pool_lv)
+        }
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        self.origin().is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct VolumeGroupReport {
     pub vg_name: String,
     pub vg_attr: String,
 
-    #[serde(deserialize_with = "from_str_strip_unit")]
-    pub vg_extent_size: u64,
+    pub vg_extent_size: ByteSize,
 
     #[serde(deserialize_with = "from_str")]
     pub pv_count: u32,
@@ -80,11 +326,9 @@ pub struct VolumeGroupReport {
     #[serde(deserialize_with = "from_str")]
     pub snap_count: u32,
 
-    #[serde(deserialize_with = "from_str_strip_unit")]
-    pub vg_size: u64,
+    pub vg_size: ByteSize,
 
-    #[serde(deserialize_with = "from_str_strip_unit")]
-    pub vg_free: u64,
+    pub vg_free: ByteSize,
 
     pub vg_uuid: String,
     pub vg_profile: String,
@@ -183,6 +427,65 @@ impl VolumeGroup {
     }
 }
 
+/// Watches a thin pool's data/metadata usage and grows it before it fills.
+///
+/// Thin pools that reach 100% corrupt every overlying volume, so unattended
+/// installs call `check` on a timer to keep a safety margin.
+pub struct ThinPool {
+    pub vg_name: String,
+    pub lv_name: String,
+    pub threshold: f64,
+    pub grow: u64,
+}
+
+impl ThinPool {
+    pub fn new(vg_name: &str, lv_name: &str, threshold: f64, grow: u64) -> ThinPool {
+        ThinPool {
+            vg_name: vg_name.into(),
+            lv_name: lv_name.into(),
+            threshold,
+            grow,
+        }
+    }
+
+    pub fn report(&self) -> Result<LogicalVolumeReport> {
+        lvs()?
+            .into_iter()
+            .filter(|lv| lv.lv_name == self.lv_name && lv.vg_name == self.vg_name)
+            .next()
+            .ok_or("Unable to get report for thin pool".into())
+    }
+
+    fn extend(&self) -> Result<()> {
+        let output = Command::new("lvextend")
+            .args(&[
+                "-L",
+                &format!("+{}B", self.grow),
+                &format!("{}/{}", self.vg_name, self.lv_name),
+            ])
+            .output()
+            .chain_err(|| "lvextend could not start")?;
+        if !output.status.success() {
+            return Err(format!(
+                "lvextend failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ).into());
+        }
+        Ok(())
+    }
+
+    /// Report current usage and grow the pool if either its data or metadata
+    /// usage has crossed `threshold`, returning the refreshed report.
+    pub fn check(&self) -> Result<LogicalVolumeReport> {
+        let report = self.report()?;
+        if report.data_percent >= self.threshold || report.metadata_percent >= self.threshold {
+            self.extend()?;
+            return self.report();
+        }
+        Ok(report)
+    }
+}
+
 pub struct LogicalVolume {
     pub name: String,
     pub path: PathBuf,
@@ -209,4 +512,256 @@ impl LogicalVolume {
             name: report.lv_name,
         }
     }
-}
\ No newline at end of file
+
+    fn volume_group(&self) -> Result<VolumeGroup> {
+        let parent = self.path
+            .parent()
+            .ok_or(ErrorKind::Msg("LogicalVolume path has no volume group".into()))?;
+        VolumeGroup::from_path(parent)
+    }
+
+    pub fn report(&self) -> Result<LogicalVolumeReport> {
+        let vg = self.volume_group()?;
+        lvs()?
+            .into_iter()
+            .filter(|lv| lv.lv_name == self.name && lv.vg_name == vg.name)
+            .next()
+            .ok_or("Unable to get report for lv".into())
+    }
+
+    pub fn remove(self) -> Result<()> {
+        let vg = self.volume_group()?;
+        let output = Command::new("lvremove")
+            .args(&["-f", &format!("{}/{}", vg.name, self.name)])
+            .output()
+            .chain_err(|| "lvremove could not start")?;
+        if !output.status.success() {
+            return Err(format!(
+                "lvremove failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ).into());
+        }
+        Ok(())
+    }
+
+    pub fn rename(self, new_name: &str) -> Result<LogicalVolume> {
+        let vg = self.volume_group()?;
+        let output = Command::new("lvrename")
+            .args(&[&vg.name, &self.name, new_name])
+            .output()
+            .chain_err(|| "lvrename could not start")?;
+        if !output.status.success() {
+            return Err(format!(
+                "lvrename failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ).into());
+        }
+        vg.volumes()?
+            .into_iter()
+            .filter(|lv| lv.name == new_name)
+            .next()
+            .ok_or("Unable to find renamed volume".into())
+    }
+
+    pub fn snapshot(&self, name: &str) -> Result<LogicalVolume> {
+        let vg = self.volume_group()?;
+        let output = Command::new("lvcreate")
+            .args(&[
+                "--snapshot",
+                "--name",
+                name,
+                &format!("{}/{}", vg.name, self.name),
+            ])
+            .output()
+            .chain_err(|| "lvcreate could not start")?;
+        if !output.status.success() {
+            return Err(format!(
+                "lvcreate failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ).into());
+        }
+        vg.volumes()?
+            .into_iter()
+            .filter(|lv| lv.name == name)
+            .next()
+            .ok_or("Unable to find new snapshot".into())
+    }
+
+    pub fn merge(self) -> Result<()> {
+        let vg = self.volume_group()?;
+        let output = Command::new("lvconvert")
+            .args(&["--merge", &format!("{}/{}", vg.name, self.name)])
+            .output()
+            .chain_err(|| "lvconvert could not start")?;
+        if !output.status.success() {
+            return Err(format!(
+                "lvconvert failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ).into());
+        }
+        Ok(())
+    }
+
+    pub fn resize(&self, new_size: u64) -> Result<LogicalVolume> {
+        let vg = self.volume_group()?;
+        let current = self.report()?.lv_size.bytes();
+        // Resizing to the current size is a no-op; LVM rejects it ("matches
+        // existing size"), so return the volume unchanged instead of shelling out.
+        if new_size == current {
+            return vg.volumes()?
+                .into_iter()
+                .filter(|lv| lv.name == self.name)
+                .next()
+                .ok_or("Unable to find resized volume".into());
+        }
+        let spec = format!("{}/{}", vg.name, self.name);
+        let size_arg = format!("{}B", new_size);
+        let tool = if new_size > current {
+            "lvextend"
+        } else {
+            "lvreduce"
+        };
+        let output = Command::new(tool)
+            .args(&["-f", "--size", &size_arg, &spec])
+            .output()
+            .chain_err(|| format!("{} could not start", tool))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                tool,
+                String::from_utf8_lossy(&output.stderr)
+            ).into());
+        }
+        vg.volumes()?
+            .into_iter()
+            .filter(|lv| lv.name == self.name)
+            .next()
+            .ok_or("Unable to find resized volume".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_size_parses_suffixes() {
+        // lowercase suffixes are powers of 1024, uppercase powers of 1000.
+        assert_eq!(parse_byte_size("4096B"), Ok(4096));
+        assert_eq!(parse_byte_size("1k"), Ok(1024));
+        assert_eq!(parse_byte_size("1K"), Ok(1000));
+        assert_eq!(parse_byte_size("512m"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1M"), Ok(1_000_000));
+        assert_eq!(parse_byte_size("1g"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1t"), Ok(1024u64.pow(4)));
+    }
+
+    #[test]
+    fn byte_size_parses_fractional_and_approximate() {
+        assert_eq!(parse_byte_size("1.50g"), Ok(1_610_612_736));
+        // `<`/`>`-prefixed approximate sizes lose the prefix before parsing.
+        assert_eq!(parse_byte_size("<1.00g"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size(">512m"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_byte_size(" 2g "), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn byte_size_rejects_garbage() {
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("12x").is_err());
+        assert!(parse_byte_size("abc").is_err());
+    }
+
+    #[test]
+    fn byte_size_round_trips_through_display() {
+        let size = ByteSize(1_610_612_736);
+        assert_eq!(size.to_string(), "1.50g");
+        assert_eq!(size.to_string().parse::<ByteSize>(), Ok(size));
+        assert_eq!(ByteSize(4096).to_string(), "4.00k");
+        assert_eq!(ByteSize(512).to_string(), "512B");
+    }
+
+    #[test]
+    fn byte_size_sub_saturates() {
+        assert_eq!(ByteSize(10) - ByteSize(4), ByteSize(6));
+        assert_eq!(ByteSize(4) - ByteSize(10), ByteSize(0));
+    }
+
+    #[test]
+    fn byte_size_serializes_as_exact_bytes() {
+        let value = serde_json::to_value(ByteSize(1_610_612_736)).unwrap();
+        assert_eq!(value, serde_json::json!(1_610_612_736u64));
+        // Both the exact integer and a unit string deserialize back correctly.
+        let from_int: ByteSize = serde_json::from_value(value).unwrap();
+        assert_eq!(from_int, ByteSize(1_610_612_736));
+        let from_str: ByteSize =
+            serde_json::from_value(serde_json::json!("1.50g")).unwrap();
+        assert_eq!(from_str, ByteSize(1_610_612_736));
+    }
+
+    #[test]
+    fn lv_attr_decodes_active_thin_volume() {
+        let attr = LvAttr::parse("Vwi-aotz--");
+        assert_eq!(attr.volume_type, VolumeType::ThinVolume);
+        assert_eq!(attr.state, VolumeState::Active);
+        assert!(attr.open);
+        assert_eq!(attr.health, VolumeHealth::Ok);
+        assert!(attr.is_active());
+        assert!(!attr.needs_repair());
+    }
+
+    #[test]
+    fn lv_attr_decodes_thin_pool_and_snapshot() {
+        let pool = LvAttr::parse("twi-aotz--");
+        assert_eq!(pool.volume_type, VolumeType::ThinPool);
+        let snap = LvAttr::parse("swi-a-s---");
+        assert_eq!(snap.volume_type, VolumeType::Snapshot);
+        assert_eq!(snap.state, VolumeState::Active);
+        assert!(!snap.open);
+    }
+
+    #[test]
+    fn lv_attr_flags_degraded_volumes() {
+        // Suspended volumes are not active; partial health and invalid
+        // snapshots need repair.
+        let suspended = LvAttr::parse("Vwi-sot---");
+        assert_eq!(suspended.state, VolumeState::Suspended);
+        assert!(!suspended.is_active());
+
+        let partial = LvAttr::parse("Vwi-ao--p-");
+        assert_eq!(partial.health, VolumeHealth::Partial);
+        assert!(partial.needs_repair());
+
+        let invalid = LvAttr::parse("swi-Ia----");
+        assert_eq!(invalid.state, VolumeState::InvalidSnapshot);
+        assert!(invalid.needs_repair());
+    }
+
+    #[test]
+    fn lv_attr_handles_short_strings() {
+        // Missing positions fall back to `-`, never panicking.
+        let attr = LvAttr::parse("");
+        assert_eq!(attr.volume_type, VolumeType::Linear);
+        assert_eq!(attr.health, VolumeHealth::Ok);
+    }
+
+    fn strip_percent(s: &str) -> ::std::result::Result<f64, serde_json::Error> {
+        from_str_strip_percent(serde_json::Value::String(s.into()))
+    }
+
+    #[test]
+    fn percent_strips_trailing_sign() {
+        assert_eq!(strip_percent("80.00%").unwrap(), 80.0);
+        assert_eq!(strip_percent("0.00%").unwrap(), 0.0);
+        assert_eq!(strip_percent("100.00%").unwrap(), 100.0);
+        // A bare number (no sign) parses too.
+        assert_eq!(strip_percent("12.5").unwrap(), 12.5);
+    }
+
+    #[test]
+    fn percent_treats_empty_as_zero() {
+        // `lvs` reports `%` or an empty string for volumes without usage data.
+        assert_eq!(strip_percent("").unwrap(), 0.0);
+        assert_eq!(strip_percent("%").unwrap(), 0.0);
+    }
+}